@@ -1,11 +1,12 @@
 use env_logger::Env;
+use regex::Regex;
 use std::io;
 use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 use inferno::flamegraph::{
-    self, color::BackgroundColor, color::PaletteMap, Direction, FuncFrameAttrsMap, Options,
-    Palette, DEFAULT_TITLE,
+    self, color::BackgroundColor, color::PaletteMap, color::SearchColor, Direction,
+    FuncFrameAttrsMap, Options, Palette, TextTruncateDirection, DEFAULT_TITLE,
 };
 
 #[derive(Debug, StructOpt)]
@@ -51,7 +52,8 @@ tools such as DTrace, perf, SystemTap, and Instruments.
  The output flame graph shows relative presence of functions in stack samples.
  The ordering on the x-axis has no meaning; since the data is samples, time
  order of events is not known.  The order used sorts function names
- alphabetically.
+ alphabetically.  If the input does carry meaningful ordering, such as a
+ time-ordered event trace, pass --flame-chart to preserve it instead.
 
  While intended to process stack samples, this can also process stack traces.
  For example, tracing stacks for memory allocation, or resource usage.  You
@@ -106,10 +108,18 @@ struct Opt {
     #[structopt(long = "bgcolors")]
     bgcolors: Option<BackgroundColor>,
 
+    /// Set the color used to pre-highlight --search matches in the rendered SVG, as "#rrggbb"
+    #[structopt(long = "search-color")]
+    search_color: Option<SearchColor>,
+
     /// Colors are keyed by function name hash
     #[structopt(long = "hash")]
     hash: bool,
 
+    /// Choose colors deterministically instead of randomly, even without --hash
+    #[structopt(long = "deterministic")]
+    deterministic: bool,
+
     /// Use consistent palette (palette.map)
     #[structopt(long = "cp")]
     cp: bool,
@@ -146,6 +156,10 @@ struct Opt {
     #[structopt(long = "fontwidth", default_value = "0.59")]
     font_width: f64,
 
+    /// Truncate long function names from the left instead of the right
+    #[structopt(long = "truncate-left")]
+    truncate_left: bool,
+
     /// Count type label
     #[structopt(long = "countname", default_value = "samples")]
     count_name: String,
@@ -162,10 +176,51 @@ struct Opt {
     #[structopt(long = "negate")]
     negate: bool,
 
+    /// Plot the flame graph in time order: keep the input's left-to-right
+    /// order and don't merge a frame with an earlier, non-adjacent
+    /// occurrence of the same function
+    #[structopt(long = "flame-chart", conflicts_with = "no_sort")]
+    flame_chart: bool,
+
+    /// Disable sorting function names alphabetically
+    #[structopt(long = "no-sort")]
+    no_sort: bool,
+
     /// Factor to scale sample counts by
     #[structopt(long = "factor", default_value = "1.0")]
     factor: f64,
 
+    /// Collapse simple recursive cycles in each stack into one occurrence
+    #[structopt(long = "collapse-recursion")]
+    collapse_recursion: bool,
+
+    /// Terminate each stack at the first frame matching REGEX
+    #[structopt(long = "prune", value_name = "REGEX")]
+    prune: Option<Regex>,
+
+    /// Drop stacks that don't contain a frame matching REGEX
+    #[structopt(long = "filter", value_name = "REGEX")]
+    filter: Option<Regex>,
+
+    /// Pre-highlight frames matching REGEX at render time
+    #[structopt(long = "search", value_name = "REGEX")]
+    search: Option<Regex>,
+
+    /// Match --search case-insensitively. This crate doesn't embed any
+    /// JavaScript in its output, so there's no in-browser Ctrl-F search to
+    /// configure; this only affects which frames get pre-highlighted at
+    /// render time.
+    #[structopt(long = "search-case-insensitive")]
+    search_case_insensitive: bool,
+
+    /// Remove frames matching REGEX from every stack (may be given multiple times)
+    #[structopt(long = "exclude", value_name = "REGEX", number_of_values = 1)]
+    exclude: Vec<Regex>,
+
+    /// Fold an excluded leaf frame's self samples into its parent instead of discarding them
+    #[structopt(long = "exclude-fold", requires = "exclude")]
+    exclude_fold: bool,
+
     /// Silence all log output
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
@@ -187,11 +242,14 @@ struct Opt {
 }
 
 impl<'a> Opt {
+    #[allow(clippy::field_reassign_with_default)]
     fn into_parts(self) -> (Vec<PathBuf>, Options<'a>) {
         let mut options = Options::default();
         options.colors = self.colors;
         options.bgcolors = self.bgcolors;
+        options.search_color = self.search_color;
         options.hash = self.hash;
+        options.deterministic = self.deterministic;
         if let Some(file) = self.nameattr_file {
             match FuncFrameAttrsMap::from_file(&file) {
                 Ok(m) => {
@@ -210,6 +268,15 @@ impl<'a> Opt {
         options.factor = self.factor;
         options.pretty_xml = self.pretty_xml;
         options.no_javascript = self.no_javascript;
+        options.flame_chart = self.flame_chart;
+        options.no_sort = self.no_sort || self.flame_chart;
+        options.collapse_recursion = self.collapse_recursion;
+        options.prune = self.prune;
+        options.filter = self.filter;
+        options.search = self.search;
+        options.search_case_insensitive = self.search_case_insensitive;
+        options.exclude = self.exclude;
+        options.exclude_fold = self.exclude_fold;
 
         // set style options
         options.subtitle = self.subtitle;
@@ -219,6 +286,9 @@ impl<'a> Opt {
         options.font_type = self.font_type;
         options.font_size = self.font_size;
         options.font_width = self.font_width;
+        if self.truncate_left {
+            options.text_truncate_direction = TextTruncateDirection::Left;
+        }
         options.count_name = self.count_name;
         options.name_type = self.name_type;
         options.notes = self.notes;
@@ -263,7 +333,7 @@ fn fetch_consistent_palette_if_needed(
 ) -> io::Result<Option<PaletteMap>> {
     let palette_map = if use_consistent_palette {
         let path = Path::new(palette_file);
-        Some(PaletteMap::load_from_file_or_empty(&path)?)
+        Some(PaletteMap::load_from_file_or_empty(path)?)
     } else {
         None
     };
@@ -277,7 +347,7 @@ fn save_consistent_palette_if_needed(
 ) -> io::Result<()> {
     if let Some(palette_map) = palette_map {
         let path = Path::new(palette_file);
-        palette_map.save_to_file(&path)?;
+        palette_map.save_to_file(path)?;
     }
 
     Ok(())
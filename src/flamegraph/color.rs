@@ -0,0 +1,270 @@
+//! Color palettes and per-frame color selection.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use rand::Rng;
+
+/// The named color palettes accepted by `--colors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Palette {
+    #[default]
+    Hot,
+    Mem,
+    Io,
+    Wakeup,
+    Java,
+    Js,
+    Perl,
+    Red,
+    Green,
+    Blue,
+    Aqua,
+    Yellow,
+    Purple,
+    Orange,
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hot" => Ok(Palette::Hot),
+            "mem" => Ok(Palette::Mem),
+            "io" => Ok(Palette::Io),
+            "wakeup" => Ok(Palette::Wakeup),
+            "java" => Ok(Palette::Java),
+            "js" => Ok(Palette::Js),
+            "perl" => Ok(Palette::Perl),
+            "red" => Ok(Palette::Red),
+            "green" => Ok(Palette::Green),
+            "blue" => Ok(Palette::Blue),
+            "aqua" => Ok(Palette::Aqua),
+            "yellow" => Ok(Palette::Yellow),
+            "purple" => Ok(Palette::Purple),
+            "orange" => Ok(Palette::Orange),
+            _ => Err(format!("unknown palette: {}", s)),
+        }
+    }
+}
+
+/// The background of the generated SVG: either a named gradient or a flat
+/// `#rrggbb` color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackgroundColor {
+    Yellow,
+    Blue,
+    Green,
+    Grey,
+    Flat(String),
+}
+
+impl FromStr for BackgroundColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yellow" => Ok(BackgroundColor::Yellow),
+            "blue" => Ok(BackgroundColor::Blue),
+            "green" => Ok(BackgroundColor::Green),
+            "grey" | "gray" => Ok(BackgroundColor::Grey),
+            _ => {
+                parse_hex_color(s)?;
+                Ok(BackgroundColor::Flat(s.to_string()))
+            }
+        }
+    }
+}
+
+fn parse_hex_color(s: &str) -> Result<(), String> {
+    let valid = s.len() == 7
+        && s.starts_with('#')
+        && s[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("not a \"#rrggbb\" color: {}", s))
+    }
+}
+
+/// The color used to pre-highlight frames matching `--search` at render
+/// time, as `#rrggbb`. This bakes the highlight into the SVG itself; it
+/// isn't tied to any in-browser interactive search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchColor(String);
+
+impl SearchColor {
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for SearchColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_hex_color(s)?;
+        Ok(SearchColor(s.to_string()))
+    }
+}
+
+/// A saved mapping from function name to color, used to keep colors
+/// consistent across multiple runs (`--cp`).
+#[derive(Debug, Default)]
+pub struct PaletteMap(HashMap<String, String>);
+
+impl PaletteMap {
+    pub fn load_from_file_or_empty(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(PaletteMap(HashMap::new()));
+        }
+        let contents = fs::read_to_string(path)?;
+        let mut map = HashMap::new();
+        for line in contents.lines() {
+            if let Some((name, color)) = line.split_once("->") {
+                map.insert(name.to_string(), color.to_string());
+            }
+        }
+        Ok(PaletteMap(map))
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        let mut contents = String::new();
+        for name in names {
+            contents.push_str(name);
+            contents.push_str("->");
+            contents.push_str(&self.0[name]);
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+
+    pub(crate) fn get_or_insert(&mut self, name: &str, palette: Palette) -> String {
+        if let Some(color) = self.0.get(name) {
+            return color.clone();
+        }
+        let color = deterministic_color(name, palette);
+        self.0.insert(name.to_string(), color.clone());
+        color
+    }
+}
+
+/// A stable (non-cryptographic) 32-bit hash, used to deterministically pick
+/// a color for a given frame name.
+fn fnv1a(name: &str) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in name.bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+fn hue_range(palette: Palette) -> (f64, f64) {
+    match palette {
+        Palette::Hot | Palette::Java | Palette::Js | Palette::Perl => (0.0, 60.0),
+        Palette::Mem => (90.0, 150.0),
+        Palette::Io | Palette::Blue | Palette::Aqua => (180.0, 240.0),
+        Palette::Wakeup | Palette::Purple => (270.0, 330.0),
+        Palette::Red => (0.0, 20.0),
+        Palette::Green => (90.0, 130.0),
+        Palette::Yellow => (50.0, 70.0),
+        Palette::Orange => (20.0, 45.0),
+    }
+}
+
+fn hsl_to_rgb_hex(h: f64, s: f64, l: f64) -> String {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+    let (r1, g1, b1) = match h as u32 {
+        0..=59 => (c, x, 0.0),
+        60..=119 => (x, c, 0.0),
+        120..=179 => (0.0, c, x),
+        180..=239 => (0.0, x, c),
+        240..=299 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let to_byte = |v: f64| (((v + m) * 255.0).round() as i64).clamp(0, 255) as u8;
+    format!("#{:02x}{:02x}{:02x}", to_byte(r1), to_byte(g1), to_byte(b1))
+}
+
+/// Pick a color for `name` deterministically, from a stable per-name hash
+/// rather than randomness, so the same name always maps to the same color.
+pub(crate) fn deterministic_color(name: &str, palette: Palette) -> String {
+    let (lo, hi) = hue_range(palette);
+    let hash = fnv1a(name);
+    let hue = lo + (hash % 1000) as f64 / 1000.0 * (hi - lo);
+    hsl_to_rgb_hex(hue, 0.6, 0.55)
+}
+
+/// Pick a color uniformly at random within the palette's hue range, the
+/// historical default behavior.
+pub(crate) fn random_color(palette: Palette) -> String {
+    let (lo, hi) = hue_range(palette);
+    let hue = rand::thread_rng().gen_range(lo, hi);
+    hsl_to_rgb_hex(hue, 0.6, 0.55)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_color_is_stable_for_the_same_name() {
+        assert_eq!(
+            deterministic_color("my_function", Palette::Hot),
+            deterministic_color("my_function", Palette::Hot)
+        );
+    }
+
+    #[test]
+    fn deterministic_color_differs_by_palette() {
+        assert_ne!(
+            deterministic_color("my_function", Palette::Hot),
+            deterministic_color("my_function", Palette::Blue)
+        );
+    }
+
+    #[test]
+    fn parses_search_color() {
+        assert_eq!(
+            SearchColor::from_str("#e600e6").unwrap().as_str(),
+            "#e600e6"
+        );
+        assert!(SearchColor::from_str("not-a-color").is_err());
+    }
+
+    #[test]
+    fn parses_background_color() {
+        assert_eq!(
+            BackgroundColor::from_str("blue").unwrap(),
+            BackgroundColor::Blue
+        );
+        assert_eq!(
+            BackgroundColor::from_str("#112233").unwrap(),
+            BackgroundColor::Flat("#112233".to_string())
+        );
+        assert!(BackgroundColor::from_str("not-a-color").is_err());
+    }
+
+    #[test]
+    fn palette_map_round_trips_through_a_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("inferno-test-palette-{}.map", std::process::id()));
+        let mut map = PaletteMap::default();
+        let color = map.get_or_insert("foo", Palette::Hot);
+        map.save_to_file(&path).unwrap();
+
+        let mut loaded = PaletteMap::load_from_file_or_empty(&path).unwrap();
+        assert_eq!(loaded.get_or_insert("foo", Palette::Hot), color);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
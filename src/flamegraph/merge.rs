@@ -0,0 +1,334 @@
+//! Parsing of folded stack lines and merging them into a call tree.
+
+use regex::Regex;
+
+/// A single folded stack: a call path and the sample count observed on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StackLine {
+    pub frames: Vec<String>,
+    pub value: u64,
+}
+
+/// Parse one line of folded input, e.g. `a;b;c 5`. Returns `None` for blank
+/// lines or lines that don't end in a count.
+pub(crate) fn parse_line(line: &str) -> Option<StackLine> {
+    let line = line.trim_end();
+    if line.is_empty() {
+        return None;
+    }
+    let space = line.rfind(' ')?;
+    let value: u64 = line[space + 1..].parse().ok()?;
+    let frames = line[..space].split(';').map(String::from).collect();
+    Some(StackLine { frames, value })
+}
+
+/// A merged call tree: each node is one frame, with the sample count that
+/// ended (self) at this frame and the children called from it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct FrameNode {
+    pub name: String,
+    pub self_value: u64,
+    pub children: Vec<FrameNode>,
+}
+
+impl FrameNode {
+    pub(crate) fn total_value(&self) -> u64 {
+        self.self_value
+            + self
+                .children
+                .iter()
+                .map(FrameNode::total_value)
+                .sum::<u64>()
+    }
+}
+
+/// Drop stacks that don't contain at least one frame matching `re`.
+pub(crate) fn apply_filter(stacks: Vec<StackLine>, re: &Regex) -> Vec<StackLine> {
+    stacks
+        .into_iter()
+        .filter(|stack| stack.frames.iter().any(|frame| re.is_match(frame)))
+        .collect()
+}
+
+/// Terminate each stack at its first frame matching `re`, discarding
+/// whatever it called (the matching frame itself is kept).
+pub(crate) fn apply_prune(stacks: &mut [StackLine], re: &Regex) {
+    for stack in stacks {
+        if let Some(idx) = stack.frames.iter().position(|frame| re.is_match(frame)) {
+            stack.frames.truncate(idx + 1);
+        }
+    }
+}
+
+/// Remove frames matching any of `excludes` from every stack, re-parenting
+/// an excluded frame's children onto its parent. When an excluded frame is
+/// the leaf of a stack, its self samples are discarded unless `fold` is set,
+/// in which case they're folded into the new leaf (its former parent).
+pub(crate) fn apply_exclude(stacks: Vec<StackLine>, excludes: &[Regex], fold: bool) -> Vec<StackLine> {
+    let is_excluded = |frame: &str| excludes.iter().any(|re| re.is_match(frame));
+    stacks
+        .into_iter()
+        .filter_map(|mut stack| {
+            let leaf_excluded = stack
+                .frames
+                .last()
+                .map(|frame| is_excluded(frame))
+                .unwrap_or(false);
+            if leaf_excluded && !fold {
+                return None;
+            }
+            stack.frames.retain(|frame| !is_excluded(frame));
+            if stack.frames.is_empty() {
+                return None;
+            }
+            Some(stack)
+        })
+        .collect()
+}
+
+/// Collapse repeating cycles of frames within a single stack into one
+/// occurrence, e.g. `a;b;a;b;a;b` becomes `a;b` and `f;f;f;g` becomes `f;g`.
+/// Walks the stack left to right; at each position, finds the smallest period
+/// whose frames repeat immediately after it and keeps only the first
+/// occurrence of that repeated run. Runs are collapsed wherever they occur.
+pub(crate) fn collapse_recursion(frames: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < frames.len() {
+        let mut collapsed = false;
+        let max_period = (frames.len() - i) / 2;
+        for period in 1..=max_period {
+            let mut repeats = 1;
+            while i + (repeats + 1) * period <= frames.len()
+                && frames[i + repeats * period..i + (repeats + 1) * period]
+                    == frames[i..i + period]
+            {
+                repeats += 1;
+            }
+            if repeats > 1 {
+                out.extend_from_slice(&frames[i..i + period]);
+                i += repeats * period;
+                collapsed = true;
+                break;
+            }
+        }
+        if !collapsed {
+            out.push(frames[i].clone());
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Merge folded stacks into a call tree, sorting sibling frames alphabetically
+/// unless `sort` is false.
+///
+/// When `adjacent_only` is set, a frame is only merged into the
+/// most-recently-added sibling at that level instead of being looked up
+/// among all of them, so stacks stay in their time order rather than
+/// collapsing every occurrence of a name together (`--flame-chart`).
+pub(crate) fn build_tree(stacks: &[StackLine], sort: bool, adjacent_only: bool) -> FrameNode {
+    let mut root = FrameNode::default();
+    for stack in stacks {
+        let mut node = &mut root;
+        for frame in &stack.frames {
+            let existing = if adjacent_only {
+                node.children
+                    .last()
+                    .filter(|c| &c.name == frame)
+                    .map(|_| node.children.len() - 1)
+            } else {
+                node.children.iter().position(|c| &c.name == frame)
+            };
+            let idx = match existing {
+                Some(idx) => idx,
+                None => {
+                    node.children.push(FrameNode {
+                        name: frame.clone(),
+                        self_value: 0,
+                        children: Vec::new(),
+                    });
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[idx];
+        }
+        node.self_value += stack.value;
+    }
+    if sort {
+        sort_children(&mut root);
+    }
+    root
+}
+
+fn sort_children(node: &mut FrameNode) {
+    node.children.sort_by(|a, b| a.name.cmp(&b.name));
+    for child in &mut node.children {
+        sort_children(child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(s: &str) -> Vec<String> {
+        s.split(';').map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_simple_line() {
+        let stack = parse_line("a;b;c 5").unwrap();
+        assert_eq!(stack.frames, frames("a;b;c"));
+        assert_eq!(stack.value, 5);
+    }
+
+    #[test]
+    fn parse_line_ignores_blank() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("   ").is_none());
+    }
+
+    #[test]
+    fn collapse_recursion_merges_an_alternating_cycle() {
+        assert_eq!(collapse_recursion(&frames("a;b;a;b;a;b")), frames("a;b"));
+    }
+
+    #[test]
+    fn collapse_recursion_merges_immediate_repeats() {
+        assert_eq!(collapse_recursion(&frames("f;f;f;g")), frames("f;g"));
+    }
+
+    #[test]
+    fn collapse_recursion_merges_a_cycle_in_the_middle_of_a_stack() {
+        assert_eq!(
+            collapse_recursion(&frames("main;recurse;recurse;done")),
+            frames("main;recurse;done")
+        );
+    }
+
+    #[test]
+    fn collapse_recursion_leaves_non_repeating_stacks_alone() {
+        assert_eq!(collapse_recursion(&frames("a;b;c")), frames("a;b;c"));
+    }
+
+    #[test]
+    fn apply_filter_drops_stacks_without_a_match() {
+        let stacks = vec![
+            StackLine { frames: frames("a;b"), value: 1 },
+            StackLine { frames: frames("a;c"), value: 2 },
+        ];
+        let re = Regex::new("^c$").unwrap();
+        let kept = apply_filter(stacks, &re);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].frames, frames("a;c"));
+    }
+
+    #[test]
+    fn apply_prune_truncates_after_the_matching_frame() {
+        let mut stacks = vec![StackLine { frames: frames("a;b;c;d"), value: 1 }];
+        let re = Regex::new("^b$").unwrap();
+        apply_prune(&mut stacks, &re);
+        assert_eq!(stacks[0].frames, frames("a;b"));
+    }
+
+    #[test]
+    fn apply_exclude_reparents_children_of_a_middle_frame() {
+        let stacks = vec![StackLine { frames: frames("a;gc;b"), value: 5 }];
+        let excludes = vec![Regex::new("^gc$").unwrap()];
+        let kept = apply_exclude(stacks, &excludes, false);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].frames, frames("a;b"));
+        assert_eq!(kept[0].value, 5);
+    }
+
+    #[test]
+    fn apply_exclude_discards_leaf_self_samples_by_default() {
+        let stacks = vec![StackLine { frames: frames("a;b;gc"), value: 5 }];
+        let excludes = vec![Regex::new("^gc$").unwrap()];
+        let kept = apply_exclude(stacks, &excludes, false);
+        assert!(kept.is_empty());
+    }
+
+    #[test]
+    fn apply_exclude_fold_keeps_leaf_self_samples() {
+        let stacks = vec![StackLine { frames: frames("a;b;gc"), value: 5 }];
+        let excludes = vec![Regex::new("^gc$").unwrap()];
+        let kept = apply_exclude(stacks, &excludes, true);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].frames, frames("a;b"));
+        assert_eq!(kept[0].value, 5);
+    }
+
+    #[test]
+    fn build_tree_sums_shared_prefixes() {
+        let stacks = vec![
+            StackLine { frames: frames("a;b"), value: 1 },
+            StackLine { frames: frames("a;c"), value: 2 },
+        ];
+        let tree = build_tree(&stacks, true, false);
+        assert_eq!(tree.children.len(), 1);
+        assert_eq!(tree.children[0].name, "a");
+        assert_eq!(tree.total_value(), 3);
+    }
+
+    #[test]
+    fn build_tree_sorts_children_alphabetically_when_requested() {
+        let stacks = vec![
+            StackLine { frames: frames("b"), value: 1 },
+            StackLine { frames: frames("a"), value: 1 },
+        ];
+        let tree = build_tree(&stacks, true, false);
+        let names: Vec<_> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn build_tree_preserves_input_order_when_not_sorting() {
+        let stacks = vec![
+            StackLine { frames: frames("b"), value: 1 },
+            StackLine { frames: frames("a"), value: 1 },
+        ];
+        let tree = build_tree(&stacks, false, false);
+        let names: Vec<_> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn build_tree_merges_non_adjacent_same_named_siblings_by_default() {
+        let stacks = vec![
+            StackLine { frames: frames("a"), value: 1 },
+            StackLine { frames: frames("b"), value: 1 },
+            StackLine { frames: frames("a"), value: 1 },
+        ];
+        let tree = build_tree(&stacks, false, false);
+        let names: Vec<_> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(tree.children[0].self_value, 2);
+    }
+
+    #[test]
+    fn build_tree_keeps_non_adjacent_same_named_siblings_apart_when_adjacent_only() {
+        let stacks = vec![
+            StackLine { frames: frames("a"), value: 1 },
+            StackLine { frames: frames("b"), value: 1 },
+            StackLine { frames: frames("a"), value: 1 },
+        ];
+        let tree = build_tree(&stacks, false, true);
+        let names: Vec<_> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "a"]);
+        assert_eq!(tree.children[0].self_value, 1);
+        assert_eq!(tree.children[2].self_value, 1);
+    }
+
+    #[test]
+    fn build_tree_still_merges_truly_adjacent_siblings_when_adjacent_only() {
+        let stacks = vec![
+            StackLine { frames: frames("a"), value: 1 },
+            StackLine { frames: frames("a"), value: 2 },
+        ];
+        let tree = build_tree(&stacks, false, true);
+        assert_eq!(tree.children.len(), 1);
+        assert_eq!(tree.children[0].self_value, 3);
+    }
+}
@@ -0,0 +1,515 @@
+//! Render folded stack samples into an interactive SVG flame graph.
+
+pub mod color;
+mod merge;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::Writer;
+use regex::{Regex, RegexBuilder};
+
+pub use self::color::Palette;
+use self::color::{BackgroundColor, PaletteMap, SearchColor};
+use self::merge::{
+    apply_exclude, apply_filter, apply_prune, build_tree, collapse_recursion, parse_line,
+    FrameNode, StackLine,
+};
+
+/// The default fill color used to highlight search matches when
+/// `Options::search_color` isn't set.
+const DEFAULT_SEARCH_COLOR: &str = "#e600e6";
+
+/// Default title used when the user hasn't set `--title`.
+pub const DEFAULT_TITLE: &str = "Flame Graph";
+
+/// Whether the flame graph is drawn root-at-bottom (the default) or
+/// root-at-top ("icicle graph", `--inverted`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Straight,
+    Inverted,
+}
+
+/// Which end of an overlong function name gets truncated for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextTruncateDirection {
+    #[default]
+    Right,
+    Left,
+}
+
+/// Truncate `name` to `max_chars` characters, keeping the end that
+/// `direction` says is most worth keeping and marking the cut with `..`.
+/// When the name doesn't fit and fewer than 3 characters do, the label is empty.
+fn truncate_name(name: &str, max_chars: usize, direction: TextTruncateDirection) -> String {
+    if name.chars().count() <= max_chars {
+        return name.to_string();
+    }
+    if max_chars < 3 {
+        return String::new();
+    }
+    match direction {
+        TextTruncateDirection::Right => {
+            let kept: String = name.chars().take(max_chars - 2).collect();
+            format!("{}..", kept)
+        }
+        TextTruncateDirection::Left => {
+            let skip = name.chars().count() - (max_chars - 2);
+            let kept: String = name.chars().skip(skip).collect();
+            format!("..{}", kept)
+        }
+    }
+}
+
+/// Per-function frame attribute overrides loaded via `--nameattr`.
+#[derive(Debug, Default, Clone)]
+pub struct FuncFrameAttrsMap(HashMap<String, HashMap<String, String>>);
+
+impl FuncFrameAttrsMap {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut map = HashMap::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let mut fields = line.split('\t');
+            let name = match fields.next() {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => continue,
+            };
+            let mut attrs = HashMap::new();
+            for field in fields {
+                if let Some((k, v)) = field.split_once('=') {
+                    attrs.insert(k.to_string(), v.to_string());
+                }
+            }
+            map.insert(name, attrs);
+        }
+        Ok(FuncFrameAttrsMap(map))
+    }
+
+    fn get(&self, name: &str) -> Option<&HashMap<String, String>> {
+        self.0.get(name)
+    }
+}
+
+/// All of the knobs that control how a flame graph is rendered.
+pub struct Options<'a> {
+    pub colors: Palette,
+    pub bgcolors: Option<BackgroundColor>,
+    pub search_color: Option<SearchColor>,
+    pub hash: bool,
+    /// Choose colors deterministically by function name hash, even when
+    /// `hash` is false, so repeated runs over the same input produce
+    /// byte-for-byte identical SVGs.
+    pub deterministic: bool,
+    pub func_frameattrs: FuncFrameAttrsMap,
+    pub direction: Direction,
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub image_width: usize,
+    pub frame_height: usize,
+    pub min_width: f64,
+    pub font_type: String,
+    pub font_size: usize,
+    pub font_width: f64,
+    /// Which end of an overlong function name gets truncated for display.
+    pub text_truncate_direction: TextTruncateDirection,
+    pub count_name: String,
+    pub name_type: String,
+    pub notes: String,
+    pub negate_differentials: bool,
+    pub factor: f64,
+    pub pretty_xml: bool,
+    pub no_javascript: bool,
+    /// Render a time-ordered flame chart: keep the input's left-to-right
+    /// stack order and only merge a frame into the sibling that came
+    /// immediately before it, so non-adjacent occurrences of the same
+    /// function stay as separate frames instead of being merged together.
+    pub flame_chart: bool,
+    /// Disable alphabetical sorting of sibling frames.
+    pub no_sort: bool,
+    /// Collapse simple recursive cycles in each stack into one occurrence.
+    pub collapse_recursion: bool,
+    /// Terminate each stack at its first frame matching this regex.
+    pub prune: Option<Regex>,
+    /// Drop stacks that don't contain a frame matching this regex.
+    pub filter: Option<Regex>,
+    /// Pre-highlight frames matching this regex at render time.
+    pub search: Option<Regex>,
+    /// Match `search` case-insensitively.
+    pub search_case_insensitive: bool,
+    /// Remove frames matching any of these regexes from every stack.
+    pub exclude: Vec<Regex>,
+    /// Fold an excluded leaf frame's self samples into its parent instead of
+    /// discarding them.
+    pub exclude_fold: bool,
+    pub palette_map: Option<&'a mut PaletteMap>,
+}
+
+impl<'a> Default for Options<'a> {
+    fn default() -> Self {
+        Options {
+            colors: Palette::default(),
+            bgcolors: None,
+            search_color: None,
+            hash: false,
+            deterministic: false,
+            func_frameattrs: FuncFrameAttrsMap::default(),
+            direction: Direction::default(),
+            title: DEFAULT_TITLE.to_string(),
+            subtitle: None,
+            image_width: 1200,
+            frame_height: 16,
+            min_width: 0.1,
+            font_type: "Verdana".to_string(),
+            font_size: 12,
+            font_width: 0.59,
+            text_truncate_direction: TextTruncateDirection::default(),
+            count_name: "samples".to_string(),
+            name_type: "Function:".to_string(),
+            notes: String::new(),
+            negate_differentials: false,
+            factor: 1.0,
+            pretty_xml: false,
+            no_javascript: false,
+            flame_chart: false,
+            no_sort: false,
+            collapse_recursion: false,
+            prune: None,
+            filter: None,
+            search: None,
+            search_case_insensitive: false,
+            exclude: Vec::new(),
+            exclude_fold: false,
+            palette_map: None,
+        }
+    }
+}
+
+/// Read folded stacks from `infiles` (or stdin if empty / `-`), merge them
+/// into a call tree, and write the resulting SVG flame graph to `writer`.
+pub fn from_files<W: Write>(
+    options: &mut Options,
+    infiles: &[PathBuf],
+    writer: W,
+) -> quick_xml::Result<()> {
+    let mut input = String::new();
+    if infiles.is_empty() || infiles.iter().all(|p| p.as_os_str() == "-") {
+        io::stdin().lock().read_to_string(&mut input)?;
+    } else {
+        for path in infiles {
+            if path.as_os_str() == "-" {
+                io::stdin().lock().read_to_string(&mut input)?;
+            } else {
+                File::open(path)?.read_to_string(&mut input)?;
+            }
+        }
+    }
+    from_reader(options, input.as_str(), writer)
+}
+
+/// Same as [`from_files`], but reads already-collected folded stacks from an
+/// in-memory string instead of from disk.
+pub fn from_reader<W: Write>(
+    options: &mut Options,
+    input: &str,
+    writer: W,
+) -> quick_xml::Result<()> {
+    let mut stacks: Vec<StackLine> = input.lines().filter_map(parse_line).collect();
+    if let Some(re) = &options.filter {
+        stacks = apply_filter(stacks, re);
+    }
+    if !options.exclude.is_empty() {
+        stacks = apply_exclude(stacks, &options.exclude, options.exclude_fold);
+    }
+    if let Some(re) = &options.prune {
+        apply_prune(&mut stacks, re);
+    }
+    if options.collapse_recursion {
+        for stack in &mut stacks {
+            stack.frames = collapse_recursion(&stack.frames);
+        }
+    }
+    for stack in &mut stacks {
+        stack.value = (stack.value as f64 * options.factor).round() as u64;
+    }
+
+    let sort = !(options.no_sort || options.flame_chart);
+    let tree = build_tree(&stacks, sort, options.flame_chart);
+
+    render(&tree, options, writer)
+}
+
+fn frame_color(name: &str, options: &mut Options) -> String {
+    if name == "-" {
+        return "#a0a0a0".to_string();
+    }
+    if let Some(map) = options.palette_map.as_deref_mut() {
+        return map.get_or_insert(name, options.colors);
+    }
+    if options.hash || options.deterministic {
+        color::deterministic_color(name, options.colors)
+    } else {
+        color::random_color(options.colors)
+    }
+}
+
+/// Rebuild `options.search` as case-insensitive when `search_case_insensitive`
+/// is set, since the regex itself is parsed before that flag is known.
+fn resolve_search_regex(options: &Options) -> Option<Regex> {
+    let search = options.search.as_ref()?;
+    if options.search_case_insensitive {
+        RegexBuilder::new(search.as_str())
+            .case_insensitive(true)
+            .build()
+            .ok()
+    } else {
+        Some(search.clone())
+    }
+}
+
+fn render<W: Write>(tree: &FrameNode, options: &mut Options, writer: W) -> quick_xml::Result<()> {
+    let total = tree.total_value().max(1) as f64;
+    let depth = max_depth(tree);
+    let height = (depth + 2) * options.frame_height;
+    let search_re = resolve_search_regex(options);
+
+    let mut xml = if options.pretty_xml {
+        Writer::new_with_indent(writer, b' ', 2)
+    } else {
+        Writer::new(writer)
+    };
+
+    let mut svg = BytesStart::owned_name("svg");
+    svg.push_attribute(("version", "1.1"));
+    svg.push_attribute(("width", options.image_width.to_string().as_str()));
+    svg.push_attribute(("height", height.to_string().as_str()));
+    svg.push_attribute(("xmlns", "http://www.w3.org/2000/svg"));
+    xml.write_event(Event::Start(svg))?;
+
+    xml.write_event(Event::Start(BytesStart::owned_name("title")))?;
+    xml.write_event(Event::Text(BytesText::from_plain_str(&options.title)))?;
+    xml.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
+
+    if let Some(subtitle) = options.subtitle.clone() {
+        let mut el = BytesStart::owned_name("text");
+        el.push_attribute(("class", "subtitle"));
+        xml.write_event(Event::Start(el))?;
+        xml.write_event(Event::Text(BytesText::from_plain_str(&subtitle)))?;
+        xml.write_event(Event::End(BytesEnd::borrowed(b"text")))?;
+    }
+
+    if !options.notes.is_empty() {
+        xml.write_event(Event::Comment(BytesText::from_plain_str(&options.notes)))?;
+    }
+
+    write_node(&mut xml, tree, 0, 0.0, total, depth, options, search_re.as_ref())?;
+
+    if !options.no_javascript {
+        xml.write_event(Event::Start(BytesStart::owned_name("script")))?;
+        xml.write_event(Event::End(BytesEnd::borrowed(b"script")))?;
+    }
+
+    xml.write_event(Event::End(BytesEnd::borrowed(b"svg")))?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_node<W: Write>(
+    xml: &mut Writer<W>,
+    node: &FrameNode,
+    depth: usize,
+    x: f64,
+    total: f64,
+    max_depth: usize,
+    options: &mut Options,
+    search_re: Option<&Regex>,
+) -> quick_xml::Result<()> {
+    if node.total_value() as f64 / total * options.image_width as f64 >= options.min_width
+        && !node.name.is_empty()
+    {
+        let width = node.total_value() as f64 / total * options.image_width as f64;
+        let y = match options.direction {
+            Direction::Straight => (max_depth - depth) * options.frame_height,
+            Direction::Inverted => depth * options.frame_height,
+        };
+
+        let color = if search_re.is_some_and(|re| re.is_match(&node.name)) {
+            options
+                .search_color
+                .as_ref()
+                .map(|c| c.as_str().to_string())
+                .unwrap_or_else(|| DEFAULT_SEARCH_COLOR.to_string())
+        } else {
+            frame_color(&node.name, options)
+        };
+        let attrs = options.func_frameattrs.get(&node.name).cloned();
+
+        xml.write_event(Event::Start(BytesStart::owned_name("g")))?;
+
+        let mut frame_title = format!(
+            "{} ({} {}, {:.2}%)",
+            node.name,
+            node.total_value(),
+            options.count_name,
+            node.total_value() as f64 / total * 100.0
+        );
+        if let Some(attrs) = &attrs {
+            if let Some(extra) = attrs.get("title") {
+                frame_title = extra.clone();
+            }
+        }
+        xml.write_event(Event::Start(BytesStart::owned_name("title")))?;
+        xml.write_event(Event::Text(BytesText::from_plain_str(&frame_title)))?;
+        xml.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
+
+        let mut rect = BytesStart::owned_name("rect");
+        rect.push_attribute(("x", x.to_string().as_str()));
+        rect.push_attribute(("y", y.to_string().as_str()));
+        rect.push_attribute(("width", width.to_string().as_str()));
+        rect.push_attribute(("height", options.frame_height.to_string().as_str()));
+        rect.push_attribute(("fill", color.as_str()));
+        xml.write_event(Event::Empty(rect))?;
+
+        let mut text = BytesStart::owned_name("text");
+        text.push_attribute(("x", (x + 2.0).to_string().as_str()));
+        text.push_attribute((
+            "y",
+            (y as f64 + options.frame_height as f64 * 0.75).to_string().as_str(),
+        ));
+        text.push_attribute(("font-family", options.font_type.as_str()));
+        text.push_attribute(("font-size", options.font_size.to_string().as_str()));
+        let max_chars = (width / (options.font_size as f64 * options.font_width)) as usize;
+        let label = truncate_name(&node.name, max_chars, options.text_truncate_direction);
+        xml.write_event(Event::Start(text))?;
+        xml.write_event(Event::Text(BytesText::from_plain_str(&label)))?;
+        xml.write_event(Event::End(BytesEnd::borrowed(b"text")))?;
+
+        xml.write_event(Event::End(BytesEnd::borrowed(b"g")))?;
+    }
+
+    let mut child_x = x;
+    for child in &node.children {
+        let child_width = child.total_value() as f64 / total * options.image_width as f64;
+        write_node(xml, child, depth + 1, child_x, total, max_depth, options, search_re)?;
+        child_x += child_width;
+    }
+    Ok(())
+}
+
+fn max_depth(node: &FrameNode) -> usize {
+    1 + node.children.iter().map(max_depth).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn search_bakes_the_highlight_color_into_matching_frames() {
+        let mut options = Options {
+            search: Some(Regex::new("^needle$").unwrap()),
+            search_color: Some(SearchColor::from_str("#112233").unwrap()),
+            no_javascript: true,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        from_reader(&mut options, "needle;haystack 1\n", &mut out).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+
+        assert!(svg.contains("fill=\"#112233\""));
+        assert!(!svg.contains(&format!("fill=\"{}\"", DEFAULT_SEARCH_COLOR)));
+    }
+
+    #[test]
+    fn search_case_insensitive_matches_regardless_of_case() {
+        let mut options = Options {
+            search: Some(Regex::new("^NEEDLE$").unwrap()),
+            search_case_insensitive: true,
+            no_javascript: true,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        from_reader(&mut options, "needle 1\n", &mut out).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+
+        assert!(svg.contains(&format!("fill=\"{}\"", DEFAULT_SEARCH_COLOR)));
+    }
+
+    #[test]
+    fn flame_chart_keeps_non_adjacent_same_named_frames_separate() {
+        let render = |flame_chart: bool, no_sort: bool| {
+            let mut options = Options {
+                flame_chart,
+                no_sort,
+                no_javascript: true,
+                ..Options::default()
+            };
+            let mut out = Vec::new();
+            from_reader(&mut options, "a 1\nb 1\na 1\n", &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // --no-sort alone still merges every "a" into one frame.
+        assert_eq!(render(false, true).matches("<g>").count(), 2);
+        // --flame-chart keeps the two non-adjacent "a" stacks as separate frames.
+        assert_eq!(render(true, false).matches("<g>").count(), 3);
+    }
+
+    #[test]
+    fn deterministic_renders_are_byte_for_byte_identical() {
+        let input = "a;b 1\na;c 2\nd 3\n";
+        let render_once = || {
+            let mut options = Options {
+                deterministic: true,
+                no_javascript: true,
+                ..Options::default()
+            };
+            let mut out = Vec::new();
+            from_reader(&mut options, input, &mut out).unwrap();
+            out
+        };
+        assert_eq!(render_once(), render_once());
+    }
+
+    #[test]
+    fn truncate_name_cuts_the_configured_end() {
+        assert_eq!(
+            truncate_name("abcdefghij", 6, TextTruncateDirection::Right),
+            "abcd.."
+        );
+        assert_eq!(
+            truncate_name("abcdefghij", 6, TextTruncateDirection::Left),
+            "..ghij"
+        );
+        assert_eq!(
+            truncate_name("short", 10, TextTruncateDirection::Right),
+            "short"
+        );
+    }
+
+    #[test]
+    fn truncate_left_keeps_the_tail_of_an_overlong_name_in_rendered_output() {
+        let mut options = Options {
+            image_width: 114,
+            text_truncate_direction: TextTruncateDirection::Left,
+            no_javascript: true,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        from_reader(
+            &mut options,
+            "some_very_long_fully_qualified_function_name 1\n",
+            &mut out,
+        )
+        .unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.contains(".._function_name"));
+    }
+}
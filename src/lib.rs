@@ -0,0 +1,3 @@
+//! A Rust port of the FlameGraph performance profiling tool suite.
+
+pub mod flamegraph;